@@ -0,0 +1,38 @@
+use anyhow::Result;
+
+// the raw command bytes used by one vendor's IPMI OEM extensions won't work
+// on another board, so this trait is the seam for adding other vendors, and
+// for the dry-run backend below. Only Supermicro (ipmi.rs) is implemented so
+// far; Dell/HP/ASRock etc. would need their own OEM command bytes, which
+// aren't verified here and so aren't included.
+pub trait FanBackend {
+    fn set_fan_to_full(&mut self) -> Result<()>;
+    fn set_fan_duty(&mut self, zone: u8, duty: u8) -> Result<()>;
+    fn read_fan_speed(&mut self) -> Result<[u64; 8]>;
+    fn reset(&mut self) -> Result<()>;
+}
+
+#[derive(Default)]
+pub struct DryRunBackend;
+
+impl FanBackend for DryRunBackend {
+    fn set_fan_to_full(&mut self) -> Result<()> {
+        info!("[dry-run] would set fans to full speed");
+        Ok(())
+    }
+
+    fn set_fan_duty(&mut self, zone: u8, duty: u8) -> Result<()> {
+        info!("[dry-run] would set zone {} fans to {}% duty", zone, duty);
+        Ok(())
+    }
+
+    fn read_fan_speed(&mut self) -> Result<[u64; 8]> {
+        info!("[dry-run] would read fan speeds");
+        Ok([0u64; 8])
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        info!("[dry-run] would perform cold reset of BMC");
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, str::FromStr};
+
+// nothing is wrong if this is missing; the defaults below are used instead
+pub const DEFAULT_PATH: &str = "/etc/ipmi-fans.conf";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlListen {
+    Disabled,
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Supermicro,
+    DryRun,
+}
+
+impl FromStr for BackendKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s {
+            "supermicro" => Ok(BackendKind::Supermicro),
+            "dry-run" => Ok(BackendKind::DryRun),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMode {
+    Curve,
+    Pid,
+}
+
+impl FromStr for ControlMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s {
+            "curve" => Ok(ControlMode::Curve),
+            "pid" => Ok(ControlMode::Pid),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reduction {
+    Max,
+    Mean,
+}
+
+impl FromStr for Reduction {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s {
+            "max" => Ok(Reduction::Max),
+            "mean" => Ok(Reduction::Mean),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZoneConfig {
+    pub id: u8,
+    pub temperature_paths: Vec<PathBuf>,
+    pub reduction: Reduction,
+    pub curve_start_temp: i32,
+    pub curve_end_temp: i32,
+    pub curve_start_level: u8,
+    pub curve_end_level: u8,
+    pub up_drag: i16,
+    pub down_drag: i16,
+}
+
+impl ZoneConfig {
+    fn defaults_for(id: u8) -> Self {
+        Self {
+            id,
+            temperature_paths: vec![
+                PathBuf::from("/sys/class/thermal/thermal_zone0/temp"),
+                PathBuf::from("/sys/class/thermal/thermal_zone1/temp"),
+            ],
+            reduction: Reduction::Mean,
+            curve_start_temp: 35 * 1000,
+            curve_end_temp: 65 * 1000,
+            curve_start_level: 15,
+            curve_end_level: 100,
+            up_drag: 4,
+            down_drag: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub backend: BackendKind,
+    pub zones: Vec<ZoneConfig>,
+    pub control_listen: ControlListen,
+    pub mode: ControlMode,
+    // note the sign convention: error is setpoint - temperature, so a
+    // cooling system (where duty must rise as temperature rises above
+    // the setpoint) needs a *negative* pid_kp
+    pub pid_kp: f64,
+    pub pid_ki: f64,
+    pub pid_kd: f64,
+    pub pid_setpoint: i32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::Supermicro,
+            zones: vec![ZoneConfig::defaults_for(0), ZoneConfig::defaults_for(1)],
+            control_listen: ControlListen::Disabled,
+            mode: ControlMode::Curve,
+            pid_kp: 0.0,
+            pid_ki: 0.0,
+            pid_kd: 0.0,
+            pid_setpoint: 50 * 1000,
+        }
+    }
+}
+
+// a partially-specified zone falls back to ZoneConfig::defaults_for
+// for whatever field it doesn't set
+#[derive(Default)]
+struct ZoneBuilder {
+    id: Option<u8>,
+    temperature_paths: Vec<PathBuf>,
+    reduction: Option<Reduction>,
+    curve_start_temp: Option<i32>,
+    curve_end_temp: Option<i32>,
+    curve_start_level: Option<u8>,
+    curve_end_level: Option<u8>,
+    up_drag: Option<i16>,
+    down_drag: Option<i16>,
+}
+
+impl ZoneBuilder {
+    fn finish(self, index: u8) -> ZoneConfig {
+        let defaults = ZoneConfig::defaults_for(index);
+
+        let curve_start_temp = self.curve_start_temp.unwrap_or(defaults.curve_start_temp);
+        let curve_end_temp = self.curve_end_temp.unwrap_or(defaults.curve_end_temp);
+        let (curve_start_temp, curve_end_temp) = if curve_end_temp > curve_start_temp {
+            (curve_start_temp, curve_end_temp)
+        } else {
+            warn!(
+                "zone.{}: curve_end_temp ({}) must be greater than curve_start_temp ({}), using defaults",
+                index, curve_end_temp, curve_start_temp
+            );
+            (defaults.curve_start_temp, defaults.curve_end_temp)
+        };
+
+        let curve_start_level = self.curve_start_level.unwrap_or(defaults.curve_start_level);
+        let curve_end_level = self.curve_end_level.unwrap_or(defaults.curve_end_level);
+        let (curve_start_level, curve_end_level) = if curve_end_level >= curve_start_level {
+            (curve_start_level, curve_end_level)
+        } else {
+            warn!(
+                "zone.{}: curve_end_level ({}) must be at least curve_start_level ({}), using defaults",
+                index, curve_end_level, curve_start_level
+            );
+            (defaults.curve_start_level, defaults.curve_end_level)
+        };
+
+        ZoneConfig {
+            id: self.id.unwrap_or(defaults.id),
+            temperature_paths: if self.temperature_paths.is_empty() {
+                defaults.temperature_paths
+            } else {
+                self.temperature_paths
+            },
+            reduction: self.reduction.unwrap_or(defaults.reduction),
+            curve_start_temp,
+            curve_end_temp,
+            curve_start_level,
+            curve_end_level,
+            up_drag: self.up_drag.unwrap_or(defaults.up_drag),
+            down_drag: self.down_drag.unwrap_or(defaults.down_drag),
+        }
+    }
+}
+
+impl Config {
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let mut config = Self::default();
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                info!(
+                    "Could not read config file {:?}, using defaults: {}",
+                    path, error
+                );
+                return config;
+            }
+        };
+
+        let mut zone_builders: BTreeMap<u8, ZoneBuilder> = BTreeMap::new();
+
+        for (lineno, raw_line) in contents.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => {
+                    warn!(
+                        "Ignoring malformed config line {}: {:?}",
+                        lineno + 1,
+                        raw_line
+                    );
+                    continue;
+                }
+            };
+            let key = key.trim();
+            let value = value.trim();
+
+            if let Some(zone_key) = key.strip_prefix("zone.") {
+                match zone_key.split_once('.') {
+                    Some((index, field)) => match index.parse::<u8>() {
+                        Ok(index) => {
+                            let zone = zone_builders.entry(index).or_default();
+                            set_zone_field(zone, field, value, key);
+                        }
+                        Err(_) => warn!("Ignoring config line with invalid zone index: {:?}", key),
+                    },
+                    None => warn!("Ignoring unknown config key {:?}", key),
+                }
+                continue;
+            }
+
+            match key {
+                "backend" => set_parsed(&mut config.backend, key, value),
+                "control_address" => config.control_listen = ControlListen::Tcp(value.to_string()),
+                "control_socket" => {
+                    config.control_listen = ControlListen::Unix(PathBuf::from(value))
+                }
+                "mode" => set_parsed(&mut config.mode, key, value),
+                "pid_kp" => set_parsed(&mut config.pid_kp, key, value),
+                "pid_ki" => set_parsed(&mut config.pid_ki, key, value),
+                "pid_kd" => set_parsed(&mut config.pid_kd, key, value),
+                "pid_setpoint" => set_parsed(&mut config.pid_setpoint, key, value),
+                _ => warn!("Ignoring unknown config key {:?}", key),
+            }
+        }
+
+        if !zone_builders.is_empty() {
+            config.zones = zone_builders
+                .into_iter()
+                .map(|(index, builder)| builder.finish(index))
+                .collect();
+        }
+
+        config
+    }
+}
+
+fn set_zone_field(zone: &mut ZoneBuilder, field: &str, value: &str, key: &str) {
+    match field {
+        "id" => set_parsed_opt(&mut zone.id, key, value),
+        "temperature_path" => zone.temperature_paths.push(PathBuf::from(value)),
+        "reduction" => set_parsed_opt(&mut zone.reduction, key, value),
+        "curve_start_temp" => set_parsed_opt(&mut zone.curve_start_temp, key, value),
+        "curve_end_temp" => set_parsed_opt(&mut zone.curve_end_temp, key, value),
+        "curve_start_level" => set_parsed_opt(&mut zone.curve_start_level, key, value),
+        "curve_end_level" => set_parsed_opt(&mut zone.curve_end_level, key, value),
+        "up_drag" => set_parsed_opt(&mut zone.up_drag, key, value),
+        "down_drag" => set_parsed_opt(&mut zone.down_drag, key, value),
+        _ => warn!("Ignoring unknown config key {:?}", key),
+    }
+}
+
+fn set_parsed<T: FromStr>(slot: &mut T, key: &str, value: &str) {
+    match value.parse() {
+        Ok(parsed) => *slot = parsed,
+        Err(_) => warn!("Ignoring invalid value {:?} for {}", value, key),
+    }
+}
+
+fn set_parsed_opt<T: FromStr>(slot: &mut Option<T>, key: &str, value: &str) {
+    match value.parse() {
+        Ok(parsed) => *slot = Some(parsed),
+        Err(_) => warn!("Ignoring invalid value {:?} for {}", value, key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    fn load_from(contents: &str) -> Config {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "ipmi-fans-test-{}-{}.conf",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, contents).unwrap();
+        let config = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn malformed_and_unknown_lines_are_ignored() {
+        let config = load_from("nonsense\nunknown_key=1\nbackend=dry-run\n");
+        assert_eq!(BackendKind::DryRun, config.backend);
+    }
+
+    #[test]
+    fn partial_zone_override_falls_back_to_defaults() {
+        let config = load_from("zone.0.curve_start_level=20\n");
+        let defaults = ZoneConfig::defaults_for(0);
+        assert_eq!(1, config.zones.len());
+        assert_eq!(20, config.zones[0].curve_start_level);
+        assert_eq!(defaults.curve_end_level, config.zones[0].curve_end_level);
+        assert_eq!(defaults.curve_start_temp, config.zones[0].curve_start_temp);
+    }
+
+    #[test]
+    fn equal_curve_temp_bounds_fall_back_to_defaults() {
+        let config = load_from(
+            "zone.0.curve_start_temp=50000\n\
+             zone.0.curve_end_temp=50000\n",
+        );
+        let defaults = ZoneConfig::defaults_for(0);
+        assert_eq!(defaults.curve_start_temp, config.zones[0].curve_start_temp);
+        assert_eq!(defaults.curve_end_temp, config.zones[0].curve_end_temp);
+    }
+
+    #[test]
+    fn inverted_curve_level_bounds_fall_back_to_defaults() {
+        let config = load_from(
+            "zone.0.curve_start_level=100\n\
+             zone.0.curve_end_level=50\n",
+        );
+        let defaults = ZoneConfig::defaults_for(0);
+        assert_eq!(
+            defaults.curve_start_level,
+            config.zones[0].curve_start_level
+        );
+        assert_eq!(defaults.curve_end_level, config.zones[0].curve_end_level);
+    }
+}
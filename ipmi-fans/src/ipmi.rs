@@ -0,0 +1,240 @@
+use crate::backend::FanBackend;
+use anyhow::Result;
+use std::{
+    convert::TryInto,
+    error::Error,
+    ffi::CStr,
+    fmt::{self, Display},
+    io,
+    mem::MaybeUninit,
+    os::raw::c_int,
+    ptr,
+};
+
+#[derive(Debug)]
+pub struct IpmiError(c_int);
+
+impl IpmiError {
+    pub fn from_context(context: libfreeipmi_sys::ipmi_ctx_t) -> Self {
+        unsafe { Self(libfreeipmi_sys::ipmi_ctx_errnum(context)) }
+    }
+}
+
+impl Error for IpmiError {}
+
+impl Display for IpmiError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> std::result::Result<(), fmt::Error> {
+        unsafe {
+            write!(
+                fmt,
+                "{}",
+                CStr::from_ptr(libfreeipmi_sys::ipmi_ctx_strerror(self.0))
+                    .to_str()
+                    .unwrap()
+            )
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FiidError(libfreeipmi_sys::fiid_err_t);
+
+impl FiidError {
+    pub fn from_context(context: libfreeipmi_sys::fiid_obj_t) -> Self {
+        unsafe { Self(libfreeipmi_sys::fiid_obj_errnum(context)) }
+    }
+}
+
+impl Error for FiidError {}
+
+impl Display for FiidError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        unsafe {
+            write!(
+                fmt,
+                "{}",
+                CStr::from_ptr(libfreeipmi_sys::fiid_strerror(self.0))
+                    .to_str()
+                    .unwrap()
+            )
+        }
+    }
+}
+
+pub struct Ipmi {
+    context: libfreeipmi_sys::ipmi_ctx_t,
+}
+
+impl Ipmi {
+    pub fn find_inband() -> Result<Self> {
+        unsafe {
+            let context = libfreeipmi_sys::ipmi_ctx_create();
+
+            if context.is_null() {
+                panic!("ipmi context malloc failed");
+            }
+
+            if -1
+                == libfreeipmi_sys::ipmi_ctx_find_inband(
+                    context,
+                    ptr::null_mut(),
+                    0,
+                    0,
+                    0,
+                    ptr::null(),
+                    0,
+                    0,
+                )
+            {
+                libfreeipmi_sys::ipmi_ctx_destroy(context);
+                return Err(IpmiError::from_context(context).into());
+            }
+
+            Ok(Self { context })
+        }
+    }
+}
+
+impl FanBackend for Ipmi {
+    fn set_fan_to_full(&mut self) -> Result<()> {
+        info!("Setting fans to full speed");
+
+        unsafe {
+            let command = [0x45, 0x01, 0x01];
+            let mut resp = [0u8; 8];
+            if -1
+                == libfreeipmi_sys::ipmi_cmd_raw(
+                    self.context,
+                    0,
+                    0x30,
+                    command.as_ptr() as *const _,
+                    command.len().try_into().unwrap(),
+                    resp.as_mut_ptr() as *mut _,
+                    resp.len().try_into().unwrap(),
+                )
+            {
+                return Err(IpmiError::from_context(self.context).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_fan_duty(&mut self, zone: u8, duty: u8) -> Result<()> {
+        info!("Setting zone {} fans to {}% duty", zone, duty);
+
+        unsafe {
+            let command = [0x70, 0x66, 0x01, zone, duty];
+            let mut resp = [0u8; 8];
+            if -1
+                == libfreeipmi_sys::ipmi_cmd_raw(
+                    self.context,
+                    0,
+                    0x30,
+                    command.as_ptr() as *const _,
+                    command.len().try_into().unwrap(),
+                    resp.as_mut_ptr() as *mut _,
+                    resp.len().try_into().unwrap(),
+                )
+            {
+                return Err(IpmiError::from_context(self.context).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_fan_speed(&mut self) -> Result<[u64; 8]> {
+        let mut result = [0u64; 8];
+
+        for fan in 0..8 {
+            unsafe {
+                let obj = FiidObj::new(&libfreeipmi_sys::tmpl_cmd_get_sensor_reading_rs)?;
+                if -1
+                    == libfreeipmi_sys::ipmi_cmd_get_sensor_reading(
+                        self.context,
+                        0x41 + fan,
+                        obj.inner,
+                    )
+                {
+                    return Err(IpmiError::from_context(self.context).into());
+                }
+                let rpm = obj.get(CStr::from_bytes_with_nul_unchecked(b"sensor_reading\0"))? * 100;
+                info!("Found fan at {} speed to be {} RPM", fan, rpm);
+                result[fan as usize] = rpm;
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        info!("Performing cold reset of BMC");
+
+        unsafe {
+            let command = [0x02];
+            let mut resp = [0u8; 8];
+            if -1
+                == libfreeipmi_sys::ipmi_cmd_raw(
+                    self.context,
+                    0,
+                    0x06,
+                    command.as_ptr() as *const _,
+                    command.len().try_into().unwrap(),
+                    resp.as_mut_ptr() as *mut _,
+                    resp.len().try_into().unwrap(),
+                )
+            {
+                return Err(IpmiError::from_context(self.context).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Ipmi {
+    fn drop(&mut self) {
+        unsafe {
+            libfreeipmi_sys::ipmi_ctx_close(self.context);
+            libfreeipmi_sys::ipmi_ctx_destroy(self.context);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FiidObj {
+    inner: libfreeipmi_sys::fiid_obj_t,
+}
+
+impl FiidObj {
+    pub fn new(template: &libfreeipmi_sys::fiid_template_t) -> Result<FiidObj> {
+        unsafe {
+            let inner = libfreeipmi_sys::fiid_obj_create(template.as_ptr() as *mut _);
+            if inner.is_null() {
+                Err(io::Error::last_os_error().into())
+            } else {
+                Ok(Self { inner })
+            }
+        }
+    }
+
+    pub fn get(&self, field: &CStr) -> Result<u64> {
+        unsafe {
+            let mut value = MaybeUninit::uninit();
+            if 0 > libfreeipmi_sys::fiid_obj_get(self.inner, field.as_ptr(), value.as_mut_ptr()) {
+                Err(FiidError::from_context(self.inner).into())
+            } else {
+                Ok(value.assume_init())
+            }
+        }
+    }
+}
+
+impl Drop for FiidObj {
+    fn drop(&mut self) {
+        unsafe {
+            libfreeipmi_sys::fiid_obj_destroy(self.inner);
+        }
+    }
+}
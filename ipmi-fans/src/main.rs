@@ -1,249 +1,28 @@
 #[macro_use]
 extern crate log;
 
+mod backend;
+mod config;
+mod ipmi;
+mod pid;
+mod server;
+
 use anyhow::Result;
+use backend::{DryRunBackend, FanBackend};
+use config::{BackendKind, Config, ControlMode, Reduction, ZoneConfig};
+use ipmi::Ipmi;
+use pid::Pid;
+use server::{ControlServer, Override, Status, ZoneStatus};
 use std::{
-    convert::{TryFrom, TryInto},
-    error::Error,
-    ffi::CStr,
-    fmt::{self, Display},
+    convert::TryFrom,
     fs::File,
     io::{self, Read},
-    mem::MaybeUninit,
-    os::raw::c_int,
     path::Path,
-    ptr,
     str::FromStr,
     thread,
     time::Duration,
 };
 
-#[derive(Debug)]
-struct IpmiError(c_int);
-
-impl IpmiError {
-    pub fn from_context(context: libfreeipmi_sys::ipmi_ctx_t) -> Self {
-        unsafe { Self(libfreeipmi_sys::ipmi_ctx_errnum(context)) }
-    }
-}
-
-impl Error for IpmiError {}
-
-impl Display for IpmiError {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> std::result::Result<(), fmt::Error> {
-        unsafe {
-            write!(
-                fmt,
-                "{}",
-                CStr::from_ptr(libfreeipmi_sys::ipmi_ctx_strerror(self.0))
-                    .to_str()
-                    .unwrap()
-            )
-        }
-    }
-}
-
-#[derive(Debug)]
-struct FiidError(libfreeipmi_sys::fiid_err_t);
-
-impl FiidError {
-    pub fn from_context(context: libfreeipmi_sys::fiid_obj_t) -> Self {
-        unsafe { Self(libfreeipmi_sys::fiid_obj_errnum(context)) }
-    }
-}
-
-impl Error for FiidError {}
-
-impl Display for FiidError {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        unsafe {
-            write!(
-                fmt,
-                "{}",
-                CStr::from_ptr(libfreeipmi_sys::fiid_strerror(self.0))
-                    .to_str()
-                    .unwrap()
-            )
-        }
-    }
-}
-
-struct Ipmi {
-    context: libfreeipmi_sys::ipmi_ctx_t,
-}
-
-impl Ipmi {
-    pub fn find_inband() -> Result<Self> {
-        unsafe {
-            let context = libfreeipmi_sys::ipmi_ctx_create();
-
-            if context.is_null() {
-                panic!("ipmi context malloc failed");
-            }
-
-            if -1
-                == libfreeipmi_sys::ipmi_ctx_find_inband(
-                    context,
-                    ptr::null_mut(),
-                    0,
-                    0,
-                    0,
-                    ptr::null(),
-                    0,
-                    0,
-                )
-            {
-                libfreeipmi_sys::ipmi_ctx_destroy(context);
-                return Err(IpmiError::from_context(context).into());
-            }
-
-            Ok(Self { context })
-        }
-    }
-
-    pub fn set_fan_to_full(&mut self) -> Result<()> {
-        info!("Setting fans to full speed");
-
-        unsafe {
-            let command = [0x45, 0x01, 0x01];
-            let mut resp = [0u8; 8];
-            if -1
-                == libfreeipmi_sys::ipmi_cmd_raw(
-                    self.context,
-                    0,
-                    0x30,
-                    command.as_ptr() as *const _,
-                    command.len().try_into().unwrap(),
-                    resp.as_mut_ptr() as *mut _,
-                    resp.len().try_into().unwrap(),
-                )
-            {
-                return Err(IpmiError::from_context(self.context).into());
-            }
-        }
-
-        Ok(())
-    }
-
-    pub fn set_fan_duty(&mut self, zone: u8, duty: u8) -> Result<(), IpmiError> {
-        info!("Setting zone {} fans to {}% duty", zone, duty);
-
-        unsafe {
-            let command = [0x70, 0x66, 0x01, zone, duty];
-            let mut resp = [0u8; 8];
-            if -1
-                == libfreeipmi_sys::ipmi_cmd_raw(
-                    self.context,
-                    0,
-                    0x30,
-                    command.as_ptr() as *const _,
-                    command.len().try_into().unwrap(),
-                    resp.as_mut_ptr() as *mut _,
-                    resp.len().try_into().unwrap(),
-                )
-            {
-                return Err(IpmiError::from_context(self.context));
-            }
-        }
-
-        Ok(())
-    }
-
-    pub fn read_fan_speed(&mut self) -> Result<[u64; 8]> {
-        let mut result = [0u64; 8];
-
-        for fan in 0..8 {
-            unsafe {
-                let obj = FiidObj::new(&libfreeipmi_sys::tmpl_cmd_get_sensor_reading_rs)?;
-                if -1
-                    == libfreeipmi_sys::ipmi_cmd_get_sensor_reading(
-                        self.context,
-                        0x41 + fan,
-                        obj.inner,
-                    )
-                {
-                    return Err(IpmiError::from_context(self.context).into());
-                }
-                let rpm = obj.get(CStr::from_bytes_with_nul_unchecked(b"sensor_reading\0"))? * 100;
-                info!("Found fan at {} speed to be {} RPM", fan, rpm);
-                result[fan as usize] = rpm;
-            }
-        }
-
-        Ok(result)
-    }
-
-    pub fn reset_bmc(&mut self) -> Result<()> {
-        info!("Performing cold reset of BMC");
-
-        unsafe {
-            let command = [0x02];
-            let mut resp = [0u8; 8];
-            if -1
-                == libfreeipmi_sys::ipmi_cmd_raw(
-                    self.context,
-                    0,
-                    0x06,
-                    command.as_ptr() as *const _,
-                    command.len().try_into().unwrap(),
-                    resp.as_mut_ptr() as *mut _,
-                    resp.len().try_into().unwrap(),
-                )
-            {
-                return Err(IpmiError::from_context(self.context).into());
-            }
-        }
-
-        Ok(())
-    }
-}
-
-impl Drop for Ipmi {
-    fn drop(&mut self) {
-        unsafe {
-            libfreeipmi_sys::ipmi_ctx_close(self.context);
-            libfreeipmi_sys::ipmi_ctx_destroy(self.context);
-        }
-    }
-}
-
-#[derive(Debug)]
-struct FiidObj {
-    inner: libfreeipmi_sys::fiid_obj_t,
-}
-
-impl FiidObj {
-    pub fn new(template: &libfreeipmi_sys::fiid_template_t) -> Result<FiidObj> {
-        unsafe {
-            let inner = libfreeipmi_sys::fiid_obj_create(template.as_ptr() as *mut _);
-            if inner.is_null() {
-                Err(io::Error::last_os_error().into())
-            } else {
-                Ok(Self { inner })
-            }
-        }
-    }
-
-    pub fn get(&self, field: &CStr) -> Result<u64> {
-        unsafe {
-            let mut value = MaybeUninit::uninit();
-            if 0 > libfreeipmi_sys::fiid_obj_get(self.inner, field.as_ptr(), value.as_mut_ptr()) {
-                Err(FiidError::from_context(self.inner).into())
-            } else {
-                Ok(value.assume_init())
-            }
-        }
-    }
-}
-
-impl Drop for FiidObj {
-    fn drop(&mut self) {
-        unsafe {
-            libfreeipmi_sys::fiid_obj_destroy(self.inner);
-        }
-    }
-}
-
 // less than 20°C is probably a sensor malfunction
 const MIN_TEMP: i32 = 20 * 1000;
 
@@ -269,23 +48,37 @@ where
     Ok(i)
 }
 
-fn read_temperature() -> Result<i32, io::Error> {
-    let temp0 = read_temperature_path("/sys/class/thermal/thermal_zone0/temp")?;
-    let temp1 = read_temperature_path("/sys/class/thermal/thermal_zone1/temp")?;
-    Ok((temp0 + temp1) / 2)
-}
+fn read_temperature(zone: &ZoneConfig) -> Result<i32, io::Error> {
+    let mut readings = zone.temperature_paths.iter().map(read_temperature_path);
 
-const MAX_RATE: u8 = 100;
-const CURVE_START_LEVEL: u8 = 15;
-const CURVE_END_LEVEL: u8 = MAX_RATE;
+    let first = match readings.next() {
+        Some(reading) => reading?,
+        None => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+    };
+    let rest = readings.collect::<Result<Vec<_>, _>>()?;
 
-fn fan_curve(temp: i32) -> u8 {
-    const CURVE_START_TEMP: i32 = 35 * 1000;
-    const CURVE_END_TEMP: i32 = 65 * 1000;
+    Ok(reduce_temperature(zone.reduction, first, &rest))
+}
+
+fn reduce_temperature(reduction: Reduction, first: i32, rest: &[i32]) -> i32 {
+    match reduction {
+        Reduction::Max => rest.iter().fold(first, |max, &reading| max.max(reading)),
+        Reduction::Mean => {
+            let mut total = first;
+            let mut count = 1i32;
+            for &reading in rest {
+                total += reading;
+                count += 1;
+            }
+            total / count
+        }
+    }
+}
 
-    let unclamped = i32::from(CURVE_START_LEVEL)
-        + i32::from(CURVE_END_LEVEL - CURVE_START_LEVEL) * (temp - CURVE_START_TEMP)
-            / (CURVE_END_TEMP - CURVE_START_TEMP);
+fn fan_curve(temp: i32, zone: &ZoneConfig) -> u8 {
+    let unclamped = i32::from(zone.curve_start_level)
+        + i32::from(zone.curve_end_level - zone.curve_start_level) * (temp - zone.curve_start_temp)
+            / (zone.curve_end_temp - zone.curve_start_temp);
 
     if unclamped < 0 {
         0
@@ -354,62 +147,147 @@ impl<'a> Iterator for Rates<'a> {
 
 impl<'a> ExactSizeIterator for Rates<'a> {}
 
-const UP_DRAG: i16 = 4;
-const DOWN_DRAG: i16 = 8;
+// one of these per zone, so one zone's fans don't drag another's
+#[derive(Default)]
+struct ZoneState {
+    history: RateHistory,
+    pid: Pid,
+}
 
 fn main() {
     env_logger::init();
 
-    let mut ipmi = Ipmi::find_inband().expect("failed to open ipmi");
+    let config = Config::load(config::DEFAULT_PATH);
+
+    let mut backend: Box<dyn FanBackend> = match config.backend {
+        BackendKind::Supermicro => Box::new(Ipmi::find_inband().expect("failed to open ipmi")),
+        BackendKind::DryRun => Box::new(DryRunBackend::default()),
+    };
 
-    ipmi.set_fan_to_full()
+    backend
+        .set_fan_to_full()
         .expect("failed to set fan speed to full");
 
-    let mut history = RateHistory::default();
+    let mut control_server = match ControlServer::bind(&config.control_listen) {
+        Ok(server) => server,
+        Err(error) => {
+            error!("Failed to start control server: {}", error);
+            None
+        }
+    };
+
+    let mut zone_states: Vec<ZoneState> =
+        config.zones.iter().map(|_| ZoneState::default()).collect();
+    let mut duty_overrides: Vec<Option<Override>> = vec![None; config.zones.len()];
+    let mut status = Status {
+        zones: vec![ZoneStatus::default(); config.zones.len()],
+        fan_speeds: [0u64; 8],
+    };
     let mut reset_lockout = 0u8;
+
     loop {
-        let temperature = read_temperature();
-        let rate = temperature.map(fan_curve).unwrap_or(255);
-        let rate = if let Some(last_rate) = history.rates().last() {
-            let diff = i16::from(rate) - i16::from(last_rate);
-            if diff > UP_DRAG {
-                debug!("{} > {}", diff, UP_DRAG);
-                u8::try_from(i16::from(last_rate) + diff - UP_DRAG).unwrap()
-            } else if diff < -DOWN_DRAG {
-                debug!("{} < -{}", diff, DOWN_DRAG);
-                u8::try_from(i16::from(last_rate) + diff + DOWN_DRAG).unwrap()
-            } else {
-                debug!("{} is close to {}", rate, last_rate);
-                last_rate
+        if let Some(server) = control_server.as_mut() {
+            for (index, change) in server.poll(&status) {
+                if let Some(slot) = duty_overrides.get_mut(index) {
+                    *slot = change;
+                }
             }
-        } else {
-            debug!("no previous rate for comparison");
-            rate
-        };
-        let rate = if rate < CURVE_START_LEVEL {
-            CURVE_START_LEVEL
-        } else if rate > CURVE_END_LEVEL {
-            CURVE_END_LEVEL
-        } else {
-            rate
-        };
-        if let Err(error) = ipmi.set_fan_duty(0, rate) {
-            error!("Failed to set zone 0 fan duty cycle: {}", error);
         }
-        if let Err(error) = ipmi.set_fan_duty(1, rate) {
-            error!("Failed to set zone 1 fan duty cycle: {}", error);
+
+        for index in 0..config.zones.len() {
+            let zone_cfg = &config.zones[index];
+            let zone_state = &mut zone_states[index];
+            let duty_override = duty_overrides[index];
+            let zone_status = &mut status.zones[index];
+
+            let temperature = read_temperature(zone_cfg);
+            zone_status.temperature = temperature.as_ref().ok().copied();
+
+            let rate = match duty_override {
+                // "full" means this zone's curve_end_level, not literal 100%;
+                // curve_end_level is whatever duty the zone's own curve tops
+                // out at, so that's the zone's notion of full speed
+                Some(Override::Full) => zone_cfg.curve_end_level,
+                Some(Override::Duty(duty)) => {
+                    // curve_start_level/curve_end_level are validated on load, but
+                    // u8::clamp panics if min > max, so don't trust that blindly here
+                    let lo = zone_cfg.curve_start_level.min(zone_cfg.curve_end_level);
+                    let hi = zone_cfg.curve_start_level.max(zone_cfg.curve_end_level);
+                    duty.clamp(lo, hi)
+                }
+                None => match config.mode {
+                    ControlMode::Curve => {
+                        let rate = temperature
+                            .map(|temp| fan_curve(temp, zone_cfg))
+                            .unwrap_or(255);
+                        let rate = if let Some(last_rate) = zone_state.history.rates().last() {
+                            let diff = i16::from(rate) - i16::from(last_rate);
+                            if diff > zone_cfg.up_drag {
+                                debug!("{} > {}", diff, zone_cfg.up_drag);
+                                u8::try_from(i16::from(last_rate) + diff - zone_cfg.up_drag)
+                                    .unwrap()
+                            } else if diff < -zone_cfg.down_drag {
+                                debug!("{} < -{}", diff, zone_cfg.down_drag);
+                                u8::try_from(i16::from(last_rate) + diff + zone_cfg.down_drag)
+                                    .unwrap()
+                            } else {
+                                debug!("{} is close to {}", rate, last_rate);
+                                last_rate
+                            }
+                        } else {
+                            debug!("no previous rate for comparison");
+                            rate
+                        };
+                        if rate < zone_cfg.curve_start_level {
+                            zone_cfg.curve_start_level
+                        } else if rate > zone_cfg.curve_end_level {
+                            zone_cfg.curve_end_level
+                        } else {
+                            rate
+                        }
+                    }
+                    ControlMode::Pid => match temperature {
+                        Ok(temp) => zone_state.pid.step(
+                            temp,
+                            config.pid_setpoint,
+                            1.0,
+                            config.pid_kp,
+                            config.pid_ki,
+                            config.pid_kd,
+                            zone_cfg.curve_start_level,
+                            zone_cfg.curve_end_level,
+                        ),
+                        Err(_) => 255,
+                    },
+                },
+            };
+
+            if let Err(error) = backend.set_fan_duty(zone_cfg.id, rate) {
+                error!(
+                    "Failed to set zone {} fan duty cycle: {}",
+                    zone_cfg.id, error
+                );
+            }
+
+            zone_state.history.push(rate);
+            zone_status.duty = rate;
+            zone_status.history = zone_state.history.rates().collect();
         }
-        history.push(rate);
 
-        if history.full() && reset_lockout == 0 {
-            let actual_speed = ipmi.read_fan_speed().unwrap_or([0u64; 8]);
+        if zone_states.iter().all(|state| state.history.full()) && reset_lockout == 0 {
+            let actual_speed = backend.read_fan_speed().unwrap_or([0u64; 8]);
+            status.fan_speeds = actual_speed;
+
+            let should_reset = zone_states.iter().any(|state| {
+                (state.history.rates().min().unwrap() > 80
+                    && *actual_speed.iter().max().unwrap() < 10_000)
+                    || (state.history.rates().max().unwrap() < 50
+                        && *actual_speed.iter().min().unwrap() > 10_000)
+            });
 
-            if (history.rates().min().unwrap() > 80 && *actual_speed.iter().max().unwrap() < 10_000)
-                || (history.rates().max().unwrap() < 50
-                    && *actual_speed.iter().min().unwrap() > 10_000)
-            {
+            if should_reset {
                 reset_lockout = 15;
-                let _ = ipmi.reset_bmc();
+                let _ = backend.reset();
             }
         }
 
@@ -420,6 +298,20 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn reduce_temperature_max_takes_the_highest_reading() {
+        assert_eq!(50, reduce_temperature(Reduction::Max, 30, &[50, 10]));
+        assert_eq!(30, reduce_temperature(Reduction::Max, 30, &[]));
+    }
+
+    #[test]
+    fn reduce_temperature_mean_averages_the_readings() {
+        assert_eq!(20, reduce_temperature(Reduction::Mean, 30, &[10]));
+        assert_eq!(30, reduce_temperature(Reduction::Mean, 30, &[]));
+    }
+
     #[test]
     fn rate_history_works() {
         let mut history = super::RateHistory::default();
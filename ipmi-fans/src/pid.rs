@@ -0,0 +1,74 @@
+#[derive(Default)]
+pub struct Pid {
+    integral: f64,
+    prev_error: Option<f64>,
+}
+
+impl Pid {
+    #[allow(clippy::too_many_arguments)]
+    pub fn step(
+        &mut self,
+        temperature: i32,
+        setpoint: i32,
+        dt: f64,
+        kp: f64,
+        ki: f64,
+        kd: f64,
+        min: u8,
+        max: u8,
+    ) -> u8 {
+        let error = f64::from(setpoint - temperature);
+        let derivative = match self.prev_error {
+            Some(prev_error) => (error - prev_error) / dt,
+            None => 0.0,
+        };
+        self.prev_error = Some(error);
+
+        let trial_integral = self.integral + error * dt;
+        let output = kp * error + ki * trial_integral + kd * derivative;
+
+        let min = f64::from(min);
+        let max = f64::from(max);
+        let clamped = output.clamp(min, max);
+
+        // only accumulate the integral when the output isn't already
+        // saturated, or it winds up while saturated and makes the fans
+        // lag badly once the temperature comes back down
+        if clamped == output {
+            self.integral = trial_integral;
+        }
+
+        clamped.round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integral_does_not_wind_up_while_saturated() {
+        let mut pid = Pid::default();
+
+        // temperature stays far above setpoint for a while: kp alone
+        // already saturates the output at max, so ki should not keep
+        // accumulating on top of that
+        for _ in 0..50 {
+            let duty = pid.step(80_000, 50_000, 1.0, -1.0, -0.1, 0.0, 0, 100);
+            assert_eq!(100, duty);
+        }
+
+        // temperature drops back to setpoint: if the integral had wound
+        // up, the output would stay pinned at max for a while instead of
+        // dropping immediately
+        let duty = pid.step(50_000, 50_000, 1.0, -1.0, -0.1, 0.0, 0, 100);
+        assert_eq!(0, duty);
+    }
+
+    #[test]
+    fn output_is_clamped_to_range() {
+        let mut pid = Pid::default();
+        assert_eq!(100, pid.step(90_000, 50_000, 1.0, -1.0, 0.0, 0.0, 0, 100));
+        assert_eq!(0, pid.step(10_000, 50_000, 1.0, -1.0, 0.0, 0.0, 0, 100));
+    }
+}
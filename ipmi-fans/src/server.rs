@@ -0,0 +1,357 @@
+use crate::config::ControlListen;
+use anyhow::Result;
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::TcpListener,
+    os::unix::net::UnixListener,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Override {
+    Duty(u8),
+    Full,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ZoneStatus {
+    pub temperature: Option<i32>,
+    pub duty: u8,
+    pub history: Vec<u8>,
+}
+
+// zones here are indexed the same way as Config::zones, i.e. in ascending
+// order of the n in the config file's zone.<n> keys, not by the IPMI fan
+// zone id they drive
+#[derive(Debug, Default, Clone)]
+pub struct Status {
+    pub zones: Vec<ZoneStatus>,
+    pub fan_speeds: [u64; 8],
+}
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+    buffer: Vec<u8>,
+}
+
+pub struct ControlServer {
+    listener: Listener,
+    connections: Vec<Connection>,
+}
+
+impl ControlServer {
+    // returns Ok(None) if control is disabled in config
+    pub fn bind(listen: &ControlListen) -> Result<Option<Self>> {
+        let listener = match listen {
+            ControlListen::Disabled => return Ok(None),
+            ControlListen::Tcp(address) => {
+                let listener = TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                Listener::Tcp(listener)
+            }
+            ControlListen::Unix(path) => {
+                // a stale socket file from an unclean shutdown would
+                // otherwise make the bind fail
+                let _ = std::fs::remove_file(path);
+                let listener = UnixListener::bind(path)?;
+                listener.set_nonblocking(true)?;
+                Listener::Unix(listener)
+            }
+        };
+
+        Ok(Some(Self {
+            listener,
+            connections: Vec::new(),
+        }))
+    }
+
+    fn accept_all(&mut self) {
+        loop {
+            let accepted: io::Result<Box<dyn Stream>> = match &self.listener {
+                Listener::Tcp(listener) => listener.accept().and_then(|(stream, _)| {
+                    stream.set_nonblocking(true)?;
+                    // IPMI setpoints are tiny single-command writes;
+                    // Nagle's algorithm would needlessly delay them
+                    stream.set_nodelay(true)?;
+                    Ok(Box::new(stream) as Box<dyn Stream>)
+                }),
+                Listener::Unix(listener) => listener.accept().and_then(|(stream, _)| {
+                    stream.set_nonblocking(true)?;
+                    Ok(Box::new(stream) as Box<dyn Stream>)
+                }),
+            };
+
+            match accepted {
+                Ok(stream) => self.connections.push(Connection {
+                    stream,
+                    buffer: Vec::new(),
+                }),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    warn!("Failed to accept control connection: {}", error);
+                    break;
+                }
+            }
+        }
+    }
+
+    // returns every (zone, override) change a client made this call; the
+    // inner None means a client released that zone's override back to
+    // automatic control. A poll can drain several buffered commands across
+    // several connections, so every change has to be returned, not just
+    // the last one.
+    pub fn poll(&mut self, status: &Status) -> Vec<(usize, Option<Override>)> {
+        self.accept_all();
+
+        let mut override_changes = Vec::new();
+        let mut closed = Vec::new();
+
+        for (index, connection) in self.connections.iter_mut().enumerate() {
+            let mut chunk = [0u8; 256];
+            loop {
+                match connection.stream.read(&mut chunk) {
+                    Ok(0) => {
+                        closed.push(index);
+                        break;
+                    }
+                    Ok(count) => connection.buffer.extend_from_slice(&chunk[..count]),
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                    Err(error) => {
+                        warn!("Control connection read failed: {}", error);
+                        closed.push(index);
+                        break;
+                    }
+                }
+            }
+
+            while let Some(newline) = connection.buffer.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = connection.buffer.drain(..=newline).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim();
+
+                let response = handle_command(line, status, &mut override_changes);
+                if let Err(error) = writeln!(connection.stream, "{}", response) {
+                    if error.kind() != ErrorKind::WouldBlock {
+                        warn!("Control connection write failed: {}", error);
+                        closed.push(index);
+                    }
+                }
+            }
+        }
+
+        closed.sort_unstable();
+        closed.dedup();
+        for index in closed.into_iter().rev() {
+            self.connections.remove(index);
+        }
+
+        override_changes
+    }
+}
+
+fn handle_command(
+    line: &str,
+    status: &Status,
+    override_changes: &mut Vec<(usize, Option<Override>)>,
+) -> String {
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+        Some("temp") => match zone(&mut parts, status) {
+            Ok(zone) => match zone.temperature {
+                Some(temp) => format!("ok {}", temp),
+                None => "error no reading".to_string(),
+            },
+            Err(error) => error,
+        },
+        Some("duty") => match zone(&mut parts, status) {
+            Ok(zone) => format!("ok {}", zone.duty),
+            Err(error) => error,
+        },
+        Some("history") => match zone(&mut parts, status) {
+            Ok(zone) => format!(
+                "ok {}",
+                zone.history
+                    .iter()
+                    .map(|rate| rate.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Err(error) => error,
+        },
+        Some("fans") => format!(
+            "ok {}",
+            status
+                .fan_speeds
+                .iter()
+                .map(|rpm| rpm.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        Some("override") => match zone_index(&mut parts, status) {
+            Ok(index) => match parts.next().and_then(|duty| duty.parse().ok()) {
+                Some(duty) => {
+                    override_changes.push((index, Some(Override::Duty(duty))));
+                    "ok".to_string()
+                }
+                None => "error usage: override <zone> <duty>".to_string(),
+            },
+            Err(error) => error,
+        },
+        Some("full") => match zone_index(&mut parts, status) {
+            Ok(index) => {
+                override_changes.push((index, Some(Override::Full)));
+                "ok".to_string()
+            }
+            Err(error) => error,
+        },
+        Some("release") => match zone_index(&mut parts, status) {
+            Ok(index) => {
+                override_changes.push((index, None));
+                "ok".to_string()
+            }
+            Err(error) => error,
+        },
+        _ => "error unknown command".to_string(),
+    }
+}
+
+fn zone_index(
+    parts: &mut std::str::SplitWhitespace<'_>,
+    status: &Status,
+) -> std::result::Result<usize, String> {
+    match parts.next().and_then(|index| index.parse::<usize>().ok()) {
+        Some(index) if index < status.zones.len() => Ok(index),
+        Some(_) => Err("error no such zone".to_string()),
+        None => Err("error usage: <command> <zone> [args...]".to_string()),
+    }
+}
+
+fn zone<'a>(
+    parts: &mut std::str::SplitWhitespace<'_>,
+    status: &'a Status,
+) -> std::result::Result<&'a ZoneStatus, String> {
+    zone_index(parts, status).map(|index| &status.zones[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Status {
+        Status {
+            zones: vec![
+                ZoneStatus {
+                    temperature: Some(42_000),
+                    duty: 50,
+                    history: vec![10, 20, 30],
+                },
+                ZoneStatus::default(),
+            ],
+            fan_speeds: [1, 2, 3, 4, 5, 6, 7, 8],
+        }
+    }
+
+    fn handle(line: &str, status: &Status) -> (String, Vec<(usize, Option<Override>)>) {
+        let mut changes = Vec::new();
+        let response = handle_command(line, status, &mut changes);
+        (response, changes)
+    }
+
+    #[test]
+    fn unknown_command_is_rejected() {
+        let (response, changes) = handle("frobnicate 0", &status());
+        assert_eq!("error unknown command", response);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn zone_index_out_of_range_is_rejected() {
+        let (response, changes) = handle("temp 2", &status());
+        assert_eq!("error no such zone", response);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn zone_index_missing_is_rejected() {
+        let (response, changes) = handle("temp", &status());
+        assert_eq!("error usage: <command> <zone> [args...]", response);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn temp_reports_the_zone_reading() {
+        let (response, _) = handle("temp 0", &status());
+        assert_eq!("ok 42000", response);
+    }
+
+    #[test]
+    fn temp_reports_no_reading_when_absent() {
+        let (response, _) = handle("temp 1", &status());
+        assert_eq!("error no reading", response);
+    }
+
+    #[test]
+    fn duty_reports_the_zone_duty() {
+        let (response, _) = handle("duty 0", &status());
+        assert_eq!("ok 50", response);
+    }
+
+    #[test]
+    fn history_reports_the_zone_history() {
+        let (response, _) = handle("history 0", &status());
+        assert_eq!("ok 10 20 30", response);
+    }
+
+    #[test]
+    fn fans_reports_all_fan_speeds() {
+        let (response, _) = handle("fans", &status());
+        assert_eq!("ok 1 2 3 4 5 6 7 8", response);
+    }
+
+    #[test]
+    fn override_sets_a_duty_for_one_zone() {
+        let (response, changes) = handle("override 0 77", &status());
+        assert_eq!("ok", response);
+        assert_eq!(vec![(0, Some(Override::Duty(77)))], changes);
+    }
+
+    #[test]
+    fn override_without_a_duty_is_rejected() {
+        let (response, changes) = handle("override 0", &status());
+        assert_eq!("error usage: override <zone> <duty>", response);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn full_overrides_one_zone() {
+        let (response, changes) = handle("full 1", &status());
+        assert_eq!("ok", response);
+        assert_eq!(vec![(1, Some(Override::Full))], changes);
+    }
+
+    #[test]
+    fn release_clears_one_zone_s_override() {
+        let (response, changes) = handle("release 0", &status());
+        assert_eq!("ok", response);
+        assert_eq!(vec![(0, None)], changes);
+    }
+
+    #[test]
+    fn handle_command_accumulates_changes_across_calls() {
+        let status = status();
+        let mut changes = Vec::new();
+        handle_command("full 0", &status, &mut changes);
+        handle_command("override 1 10", &status, &mut changes);
+        assert_eq!(
+            vec![(0, Some(Override::Full)), (1, Some(Override::Duty(10)))],
+            changes
+        );
+    }
+}